@@ -1,7 +1,4 @@
-use crate::{
-    core::{Cow, String, Vec},
-    ParseError,
-};
+use crate::lib;
 
 // Single quotes are not used at all by the cmd.exe command processor except to enclose the command
 // to run within a FOR /F statement:
@@ -22,32 +19,57 @@ use crate::{
 // 3. If quoting is off, then you can escape a " as ^" to prevent it from turning quoting on. But
 //    once quoting is on, you cannot escape the closing ". The very next " will always turn quoting
 //    off.
-const SINGLE_QUOTE: char = '\'';
 const DOUBLE_QUOTE: char = '"';
 
 // Delimiters for arguments - any of these will work!
 const DELIMITER_COMMA: char = ',';
-const DELIMITER_SEMICOLON: char = ':';
+const DELIMITER_SEMICOLON: char = ';';
+const DELIMITER_COLON: char = ':';
 const DELIMITER_EQUALS: char = '=';
 const DELIMITER_SPACE: char = ' ';
 const DELIMITER_TAB: char = '\t';
 
 // Escape for use with itself and the command characters
 const ESCAPE: char = '^';
-const COMMAND_COLON: char = ':';
-const COMMAND_AMPERSAND: char = '&';
-const COMMAND_BACKSLASH: char = '\\';
-const COMMAND_LESSTHAN: char = '<';
-const COMMAND_GREATERTHAN: char = '>';
-const COMMAND_CARET: char = '^';
-const COMMAND_PIPE: char = '|';
 
+// Percent is used to reference variables and is escaped by doubling it
+const PERCENT: char = '%';
+
+// Carriage return and line feed, used to detect `^`-driven line continuations
+const CARRIAGE_RETURN: char = '\r';
+const LINE_FEED: char = '\n';
+
+// Metacharacters recognized by the command processor. An unquoted, unescaped occurrence of any of
+// these terminates the current simple command.
+const METACHAR_AMPERSAND: char = '&';
+const METACHAR_PIPE: char = '|';
+const METACHAR_LESS_THAN: char = '<';
+const METACHAR_GREATER_THAN: char = '>';
+const METACHAR_OPEN_PAREN: char = '(';
+const METACHAR_CLOSE_PAREN: char = ')';
+
+/// A single token produced by the `cmd.exe` command-processor pass.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Token {
+    /// An argument token belonging to a simple command.
+    Arg(lib::String),
+
+    /// A metacharacter (`&`, `|`, `<`, `>`, `(`, `)`) that separates simple commands.
+    Separator(char),
+}
+
+/// Iterator over the tokens of a `cmd.exe` command line.
+///
+/// This models the command-processor tokenization pass as a Delimiter / Unquoted / Quoted state
+/// machine, emitting [`Token::Separator`] for each unquoted metacharacter and [`Token::Arg`] for
+/// each argument. Because the caret escape and `""` doubling rewrite the input, each argument is an
+/// owned [`String`](lib::String) rather than a borrow of the source.
 #[derive(Default)]
 struct Split<'a> {
-    ///
+    /// Command line being tokenized
     inner: &'a str,
 
-    /// Position within inner str
+    /// Byte offset of the next unconsumed character within `inner`
     idx: usize,
 }
 
@@ -58,21 +80,166 @@ impl<'a> Split<'a> {
 }
 
 impl<'a> core::iter::Iterator for Split<'a> {
-    type Item = &'a str;
+    type Item = Token;
 
     fn next(&mut self) -> Option<Self::Item> {
-        None
+        let rest = &self.inner[self.idx..];
+        let mut chars = rest.chars().peekable();
+
+        // Bytes consumed from `rest` so far, used to advance `idx` for the next call
+        let mut consumed = 0;
+        let mut token = lib::String::new();
+        let mut started = false;
+        let mut in_quotes = false;
+
+        // Delimiter state: skip any run of delimiter characters before the token begins
+        while let Some(&c) = chars.peek() {
+            if is_delimiter_char(c) {
+                chars.next();
+                consumed += c.len_utf8();
+            } else {
+                break;
+            }
+        }
+
+        // An unquoted metacharacter forms a separator token of its own
+        if let Some(&c) = chars.peek() {
+            if is_metacharacter(c) {
+                consumed += c.len_utf8();
+                self.idx += consumed;
+                return Some(Token::Separator(c));
+            }
+        }
+
+        while let Some(c) = chars.next() {
+            consumed += c.len_utf8();
+
+            if is_escape_char(c) && !in_quotes {
+                // Note: the chunk0-1 request asked for the caret to escape regardless of state
+                // (including inside double quotes), but chunk1-6 reworked this same module to
+                // match real cmd.exe, where a caret inside a quoted region is a literal `^` (see
+                // `should_treat_caret_as_literal_inside_quotes`). The two requests contradict each
+                // other here; we honor chunk1-6 because it reflects actual cmd.exe behavior.
+                //
+                // Outside quotes the caret escapes the next character literally and drops a
+                // trailing CRLF as a line continuation; inside quotes a caret is a literal `^`.
+                match chars.peek().copied() {
+                    Some(CARRIAGE_RETURN) => {
+                        chars.next();
+                        consumed += CARRIAGE_RETURN.len_utf8();
+                        if chars.peek() == Some(&LINE_FEED) {
+                            chars.next();
+                            consumed += LINE_FEED.len_utf8();
+                        }
+                    }
+                    // `^^!` collapses all the way to a literal `!` so that delayed-expansion
+                    // escaping survives the tokenizer intact.
+                    Some(ESCAPE) => {
+                        chars.next();
+                        consumed += ESCAPE.len_utf8();
+                        if chars.peek() == Some(&'!') {
+                            chars.next();
+                            consumed += '!'.len_utf8();
+                            token.push('!');
+                        } else {
+                            token.push(ESCAPE);
+                        }
+                        started = true;
+                    }
+                    Some(next) => {
+                        chars.next();
+                        consumed += next.len_utf8();
+                        token.push(next);
+                        started = true;
+                    }
+                    // A trailing caret is simply dropped
+                    None => started = true,
+                }
+                continue;
+            }
+
+            if c == DOUBLE_QUOTE {
+                if in_quotes {
+                    if chars.peek() == Some(&DOUBLE_QUOTE) {
+                        // A doubled quote inside a quoted region is a literal quote
+                        chars.next();
+                        consumed += DOUBLE_QUOTE.len_utf8();
+                        token.push(DOUBLE_QUOTE);
+                    } else {
+                        in_quotes = false;
+                    }
+                } else {
+                    in_quotes = true;
+                }
+                started = true;
+                continue;
+            }
+
+            if !in_quotes {
+                if c == PERCENT {
+                    // `%%` collapses to a single percent
+                    if chars.peek() == Some(&PERCENT) {
+                        chars.next();
+                        consumed += PERCENT.len_utf8();
+                    }
+                    token.push(PERCENT);
+                    started = true;
+                    continue;
+                }
+
+                if is_delimiter_char(c) {
+                    // Leave the delimiter unconsumed so the next call skips it
+                    consumed -= c.len_utf8();
+                    break;
+                }
+
+                if is_metacharacter(c) {
+                    // Leave the metacharacter unconsumed so the next call emits it as a separator
+                    consumed -= c.len_utf8();
+                    break;
+                }
+            }
+
+            token.push(c);
+            started = true;
+        }
+
+        self.idx += consumed;
+
+        if started {
+            Some(Token::Arg(token))
+        } else {
+            None
+        }
     }
 }
 
-pub fn split(s: &str) -> impl core::iter::Iterator<Item = &str> {
+/// Tokenizes a `cmd.exe` command line into arguments and command separators.
+///
+/// Use this when a tool needs to reason about the full structure of a string such as
+/// `cmd /c "a & b"`, rather than only the arguments of the first command.
+pub fn tokenize(s: &str) -> impl core::iter::Iterator<Item = Token> + '_ {
     Split::new(s)
 }
 
+/// Splits a `cmd.exe` command line into the arguments of its first simple command.
+///
+/// Tokenization stops at the first unquoted metacharacter; use [`tokenize`] to also recover the
+/// command separators and the arguments of any subsequent commands.
+pub fn split(s: &str) -> impl core::iter::Iterator<Item = lib::String> + '_ {
+    Split::new(s)
+        .take_while(|t| matches!(t, Token::Arg(_)))
+        .map(|t| match t {
+            Token::Arg(a) => a,
+            Token::Separator(_) => unreachable!(),
+        })
+}
+
 #[inline]
 fn is_delimiter_char(c: char) -> bool {
     c == DELIMITER_COMMA
         || c == DELIMITER_SEMICOLON
+        || c == DELIMITER_COLON
         || c == DELIMITER_EQUALS
         || c == DELIMITER_SPACE
         || c == DELIMITER_TAB
@@ -84,67 +251,103 @@ fn is_escape_char(c: char) -> bool {
 }
 
 #[inline]
-fn is_command_char(c: char) -> bool {
-    c == COMMAND_COLON
-        || c == COMMAND_AMPERSAND
-        || c == COMMAND_BACKSLASH
-        || c == COMMAND_LESSTHAN
-        || c == COMMAND_GREATERTHAN
-        || c == COMMAND_CARET
-        || c == COMMAND_PIPE
+fn is_metacharacter(c: char) -> bool {
+    c == METACHAR_AMPERSAND
+        || c == METACHAR_PIPE
+        || c == METACHAR_LESS_THAN
+        || c == METACHAR_GREATER_THAN
+        || c == METACHAR_OPEN_PAREN
+        || c == METACHAR_CLOSE_PAREN
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn split_vec(s: &str) -> lib::Vec<lib::String> {
+        split(s).collect()
+    }
+
     #[test]
     fn should_return_entire_string_if_single_word() {
-        todo!();
+        assert_eq!(split_vec("word"), &["word"]);
     }
 
     #[test]
     fn should_split_by_delimiters() {
-        todo!();
+        assert_eq!(
+            split_vec("a,b;c:d=e f\tg"),
+            &["a", "b", "c", "d", "e", "f", "g"]
+        );
     }
 
     #[test]
     fn should_support_quoted_words_with_delimiters_inside() {
-        todo!();
+        assert_eq!(split_vec(r#""a,b c""#), &["a,b c"]);
     }
 
     #[test]
     fn should_support_escaping_command_characters() {
-        todo!("should support ^| or ^");
+        assert_eq!(split_vec("^|"), &["|"]);
+        assert_eq!(split_vec("a^&b"), &["a&b"]);
     }
 
     #[test]
     fn should_support_escaping_crlf_endings() {
-        todo!(r"should support ^\r\n at end of line");
+        assert_eq!(split_vec("a^\r\nb"), &["ab"]);
     }
 
     #[test]
     fn should_support_escaping_the_percent_character() {
-        todo!("should support %%");
+        assert_eq!(split_vec("%%"), &["%"]);
     }
 
     #[test]
     fn should_support_escaping_the_escape_character() {
-        todo!("should support ^^");
+        assert_eq!(split_vec("^^"), &["^"]);
     }
 
     #[test]
     fn should_support_double_quotes_to_group_words() {
-        todo!(r#"should support "some words" in quotes"#);
+        assert_eq!(split_vec(r#""some words""#), &["some words"]);
     }
 
     #[test]
     fn should_support_adjacent_double_quotes_to_escape_double_quotes() {
-        todo!(r#"Should support "" as an escape"#);
+        assert_eq!(split_vec(r#""a""b""#), &["a\"b"]);
     }
 
     #[test]
     fn should_support_escaping_exclamation_marks() {
-        todo!("Needs to be ^^!");
+        assert_eq!(split_vec("^^!"), &["!"]);
+    }
+
+    #[test]
+    fn should_stop_split_at_first_metacharacter() {
+        assert_eq!(split_vec("a b & c d"), &["a", "b"]);
+        assert_eq!(split_vec("ping host | find x"), &["ping", "host"]);
+    }
+
+    #[test]
+    fn should_tokenize_commands_and_separators() {
+        assert_eq!(
+            tokenize("a b & c").collect::<lib::Vec<_>>(),
+            &[
+                Token::Arg("a".into()),
+                Token::Arg("b".into()),
+                Token::Separator('&'),
+                Token::Arg("c".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_treat_quoted_metacharacters_as_literal() {
+        assert_eq!(split_vec(r#""a & b""#), &["a & b"]);
+    }
+
+    #[test]
+    fn should_treat_caret_as_literal_inside_quotes() {
+        assert_eq!(split_vec(r#""a^b""#), &["a^b"]);
     }
 }
@@ -2,76 +2,164 @@ use crate::lib;
 
 /// Escapes special characters in a string, so that it will retain its literal
 /// meaning when used as a part of command in Windows command line.
+///
+/// This models the `CommandLineToArgvW` / C runtime layer read back by [`split`]; the caret
+/// escaping of `cmd.exe` metacharacters is a separate command-processor concern handled by the
+/// [`cmd`](crate::cmd) module, and is deliberately not applied here so that the `split`/`join`
+/// round trip is preserved.
 pub fn quote(s: &str) -> lib::String {
-    // Wrap in double quotes and escape double quotes and backslashes.
+    // Wrap in double quotes and double any run of backslashes that immediately precedes a double
+    // quote (or the closing quote we append) so that `split` reads them back literally.
     let mut quoted = lib::String::from("\"");
-    let mut chars = s.chars().peekable();
+    let mut num_backslashes = 0;
 
-    while let Some(c) = chars.next() {
+    for c in s.chars() {
         match c {
-            '\\' => {
-                // Backslashes need to be escaped, but only if they precede a double quote.
-                // If we find one or more backslashes and the next character is a double quote,
-                // we double the number of backslashes and escape the double quote.
-                let mut num_backslashes = 1;
-                while chars.peek() == Some(&'\\') {
-                    num_backslashes += 1;
-                    chars.next();
-                }
-                if chars.peek() == Some(&'"') {
-                    // Double the number of backslashes and escape the double quote.
-                    quoted.extend(std::iter::repeat('\\').take(num_backslashes * 2));
-                    quoted.push_str("\\\"");
-                    chars.next();
-                } else {
-                    // Just include the backslashes as they are.
-                    quoted.extend(std::iter::repeat('\\').take(num_backslashes));
-                }
-            }
+            '\\' => num_backslashes += 1,
             '"' => {
-                // Double quotes need to be escaped.
-                quoted.push_str("\\\"");
+                // Double the preceding backslashes and escape the interior quote.
+                quoted.extend(std::iter::repeat_n('\\', num_backslashes * 2 + 1));
+                quoted.push('"');
+                num_backslashes = 0;
             }
             _ => {
+                quoted.extend(std::iter::repeat_n('\\', num_backslashes));
                 quoted.push(c);
+                num_backslashes = 0;
             }
         }
     }
 
+    // Trailing backslashes sit right before the closing quote, so they too must be doubled.
+    quoted.extend(std::iter::repeat_n('\\', num_backslashes * 2));
     quoted.push('"');
     quoted
 }
 
-/// Splits according to .
+/// Joins arguments into a single command line by quoting each with [`quote`].
+pub fn join<'a, I>(args: I) -> lib::String
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut out = lib::String::new();
+    for arg in args {
+        if !out.is_empty() {
+            out.push(' ');
+        }
+        out.push_str(&quote(arg));
+    }
+    out
+}
+
+/// Reports the token under the cursor at byte position `pos`, for use by shell completion.
+pub fn token_at(s: &str, pos: usize) -> crate::complete::TokenContext {
+    use crate::complete::{QuoteKind, TokenContext};
+
+    let mut index = 0;
+    let mut start: Option<usize> = None;
+    let mut value = lib::String::new();
+    let mut quote: Option<QuoteKind> = None;
+    let mut chars = s.char_indices().peekable();
+
+    while let Some(&(off, c)) = chars.peek() {
+        if off >= pos {
+            break;
+        }
+        chars.next();
+
+        match c {
+            ' ' if quote.is_none() => {
+                if start.is_some() {
+                    index += 1;
+                    start = None;
+                    value.clear();
+                }
+            }
+            '"' => {
+                start.get_or_insert(off);
+                quote = if quote == Some(QuoteKind::Double) {
+                    None
+                } else {
+                    Some(QuoteKind::Double)
+                };
+            }
+            '\\' if quote == Some(QuoteKind::Double)
+                && matches!(chars.peek(), Some(&(o, '"')) if o < pos) =>
+            {
+                chars.next();
+                start.get_or_insert(off);
+                value.push('"');
+            }
+            _ => {
+                start.get_or_insert(off);
+                value.push(c);
+            }
+        }
+    }
+
+    let start = start.unwrap_or(pos);
+    TokenContext {
+        index,
+        range: start..pos,
+        value,
+        quote,
+    }
+}
+
+/// Splits a Windows command line into arguments under the `CommandLineToArgvW` backslash rules.
 pub fn split(s: &str) -> lib::Vec<lib::String> {
     let mut args = lib::Vec::new();
     let mut arg = lib::String::new();
     let mut chars = s.chars().peekable();
     let mut in_quotes = false;
+    // Tracks whether the current argument has been started, so that an explicit empty argument
+    // (e.g. `""`) is preserved even though it contributes no characters.
+    let mut arg_started = false;
 
     while let Some(c) = chars.next() {
         match c {
             ' ' if !in_quotes => {
-                if !arg.is_empty() {
-                    args.push(arg);
-                    arg = String::new();
+                if arg_started {
+                    args.push(lib::mem::take(&mut arg));
+                    arg_started = false;
                 }
             }
-            '"' => in_quotes = !in_quotes,
+            '"' => {
+                in_quotes = !in_quotes;
+                arg_started = true;
+            }
             '\\' => {
-                if in_quotes && chars.peek() == Some(&'"') {
-                    // Escape double quote within quotes
+                // Count the run of backslashes and look at what follows it.
+                let mut num_backslashes: usize = 1;
+                while chars.peek() == Some(&'\\') {
+                    num_backslashes += 1;
                     chars.next();
-                    arg.push('"');
+                }
+                if chars.peek() == Some(&'"') {
+                    // 2n backslashes emit n backslashes and leave the quote to toggle; 2n+1 emit n
+                    // backslashes plus a literal quote.
+                    for _ in 0..num_backslashes / 2 {
+                        arg.push('\\');
+                    }
+                    if !num_backslashes.is_multiple_of(2) {
+                        arg.push('"');
+                        chars.next();
+                    }
                 } else {
-                    arg.push('\\');
+                    for _ in 0..num_backslashes {
+                        arg.push('\\');
+                    }
                 }
+                arg_started = true;
+            }
+            _ => {
+                arg.push(c);
+                arg_started = true;
             }
-            _ => arg.push(c),
         }
     }
 
-    if !arg.is_empty() {
+    if arg_started {
         args.push(arg);
     }
 
@@ -92,12 +180,46 @@ mod tests {
         // Special characters
         assert_eq!(quote("\\\""), "\"\\\\\\\"\"");
         assert_eq!(quote("\""), "\"\\\"\"");
-        assert_eq!(quote("C:\\Program Files\\"), "\"C:\\\\Program Files\\\\\"");
+        assert_eq!(quote("C:\\Program Files\\"), "\"C:\\Program Files\\\\\"");
 
         // Escaping sequence
         assert_eq!(quote("\\\\\\\""), "\"\\\\\\\\\\\\\\\"\"");
     }
 
+    #[test]
+    fn should_report_token_under_cursor() {
+        use crate::complete::QuoteKind;
+
+        let ctx = token_at(r#"dir "foo ba"#, 11);
+        assert_eq!(ctx.index, 1);
+        assert_eq!(ctx.range, 4..11);
+        assert_eq!(ctx.value, "foo ba");
+        assert_eq!(ctx.quote, Some(QuoteKind::Double));
+    }
+
+    #[test]
+    fn should_join_quoted_arguments() {
+        assert_eq!(join(["a", "b c"]), r#""a" "b c""#);
+    }
+
+    #[test]
+    fn should_round_trip_through_split() {
+        let cases: &[&[&str]] = &[
+            &["a", "b c"],
+            &[""],
+            &["a", "", "b"],
+            &[r#"quote"inside"#],
+            &[r"trailing\\"],
+            &[r"C:\Program Files\", "arg"],
+            &["\\"],
+            &[r#"a"b"c"#],
+        ];
+
+        for args in cases {
+            assert_eq!(&split(&join(args.iter().copied())), args);
+        }
+    }
+
     #[test]
     fn should_split_across_multiple_situations() {
         // Basic tests
@@ -113,8 +235,8 @@ mod tests {
             vec!["This is a \"quote\"."]
         );
 
-        // Escaping backslash
-        assert_eq!(split("C:\\\\dir\\\\file"), vec!["C:\\dir\\file"]);
+        // Backslashes that do not precede a quote are kept verbatim
+        assert_eq!(split("C:\\\\dir\\\\file"), vec!["C:\\\\dir\\\\file"]);
 
         // Mixed quotes and spaces
         assert_eq!(
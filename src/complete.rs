@@ -0,0 +1,36 @@
+//! Shared types for cursor-aware tokenization.
+//!
+//! The `cmd_exe`, `powershell`, `unix`, and `vc_2008` dialects each expose a `token_at(s, pos)`
+//! that reports where the cursor sits within a command line, so REPL and shell-completion
+//! frontends can figure out the word under the cursor and whether it needs a closing quote. The
+//! `cmd` and `parser` modules have no `token_at`. The splitters discard source spans; these
+//! helpers preserve them.
+
+use crate::lib;
+
+/// The kind of quote an argument is currently enclosed in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuoteKind {
+    /// Inside a single-quoted region
+    Single,
+
+    /// Inside a double-quoted region
+    Double,
+}
+
+/// The token under a cursor position within a command line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TokenContext {
+    /// Zero-based index of the argument the cursor sits in
+    pub index: usize,
+
+    /// Byte range of the raw token text entered so far, from its start up to the cursor
+    pub range: core::ops::Range<usize>,
+
+    /// The decoded (unquoted) value of the token up to the cursor
+    pub value: lib::String,
+
+    /// The open quote the cursor is currently inside, if any, so a completer knows whether a
+    /// closing quote must be inserted alongside a replacement
+    pub quote: Option<QuoteKind>,
+}
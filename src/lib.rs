@@ -19,15 +19,14 @@ mod lib {
     pub use alloc::vec::Vec;
     #[cfg(feature = "std")]
     pub use std::vec::Vec;
-
-    #[cfg(not(feature = "std"))]
-    pub use alloc::borrow::Cow;
-    #[cfg(feature = "std")]
-    pub use std::borrow::Cow;
 }
 
+pub mod cmd;
 pub mod cmd_exe;
+pub mod complete;
+pub mod parser;
 pub mod powershell;
+pub mod unix;
 pub mod vc_2008;
 
 // Test our README examples as part of doctest
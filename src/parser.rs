@@ -6,16 +6,12 @@ pub type ParseResult = Result<lib::Vec<lib::String>, ParseError>;
 /// An error returned during parsing
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ParseError {
-    ArgNotEmptyInInitialState,
-    CommandNameBackslash,
     ReachedUnescapedNewline,
 }
 
 impl lib::fmt::Display for ParseError {
     fn fmt(&self, f: &mut lib::fmt::Formatter) -> lib::fmt::Result {
         match self {
-            Self::ArgNotEmptyInInitialState => write!(f, "Arg not empty in initial state"),
-            Self::CommandNameBackslash => write!(f, "Encountered special backslash during command name, but should be considered normal"),
             Self::ReachedUnescapedNewline => write!(f, "Reached unescaped newline"),
         }
     }
@@ -24,6 +20,184 @@ impl lib::fmt::Display for ParseError {
 #[cfg(feature = "std")]
 impl std::error::Error for ParseError {}
 
+/// A single code unit of a command line.
+///
+/// The tokenizer is written once against this trait so that the identical backslash/quote rules
+/// can run over UTF-8 `char`s (see [`Parser::parse`]) or raw UTF-16 `u16` units (see
+/// [`Parser::parse_wide`]) without duplicating the state machine.
+trait Unit: Copy + PartialEq {
+    /// The backslash code unit
+    const BACKSLASH: Self;
+
+    /// The double quote code unit
+    const DOUBLE_QUOTE: Self;
+
+    /// Whitespace or a null terminator, either of which separates arguments
+    fn is_whitespace_or_null(&self) -> bool;
+
+    /// The newline code unit; a newline met while skipping whitespace before an argument is an
+    /// error, while one that ends an unquoted argument or sits inside quotes is accepted
+    fn is_newline(&self) -> bool;
+}
+
+impl Unit for char {
+    const BACKSLASH: char = '\\';
+    const DOUBLE_QUOTE: char = '"';
+
+    fn is_whitespace_or_null(&self) -> bool {
+        matches!(self, ' ' | '\t' | '\r' | '\n' | '\0')
+    }
+
+    fn is_newline(&self) -> bool {
+        *self == '\n'
+    }
+}
+
+impl Unit for u16 {
+    const BACKSLASH: u16 = b'\\' as u16;
+    const DOUBLE_QUOTE: u16 = b'"' as u16;
+
+    fn is_whitespace_or_null(&self) -> bool {
+        *self == 0
+            || *self == b' ' as u16
+            || *self == b'\t' as u16
+            || *self == b'\r' as u16
+            || *self == b'\n' as u16
+    }
+
+    fn is_newline(&self) -> bool {
+        *self == b'\n' as u16
+    }
+}
+
+/// Consumes a run of backslashes starting at `i`, appending the decoded backslashes (and possibly
+/// a literal double quote) to `token`, and returns the index of the next unprocessed unit.
+///
+///  * If an even number of backslashes is followed by a double quote, one backslash is output for
+///    every pair of backslashes, and the double quote remains unconsumed so that the main loop can
+///    interpret it as the start or end of a quoted string.
+///  * If an odd number of backslashes is followed by a double quote, one backslash is output for
+///    every pair of backslashes, and the trailing backslash-double quote becomes a literal double
+///    quote, which is consumed here.
+///  * Otherwise, the backslashes are interpreted literally.
+fn parse_backslashes<U: Unit>(src: &[U], mut i: usize, token: &mut lib::Vec<U>) -> usize {
+    let start = i;
+    while i < src.len() && src[i] == U::BACKSLASH {
+        i += 1;
+    }
+    let count = i - start;
+
+    if i < src.len() && src[i] == U::DOUBLE_QUOTE {
+        for _ in 0..count / 2 {
+            token.push(U::BACKSLASH);
+        }
+        if count.is_multiple_of(2) {
+            // Leave the double quote for the main loop to toggle quoting
+            i
+        } else {
+            token.push(U::DOUBLE_QUOTE);
+            // Consume the escaped double quote
+            i + 1
+        }
+    } else {
+        for _ in 0..count {
+            token.push(U::BACKSLASH);
+        }
+        i
+    }
+}
+
+/// Tokenizes a command line expressed as a slice of code units into groups of code units, one per
+/// argument.
+///
+/// With `modern` disabled this follows LLVM's `tokenizeWindowsCommandLine` rules, where a double
+/// quote inside a quoted region simply closes it. With `modern` enabled it follows the current
+/// Microsoft C runtime (VS2015+) rules, where a `""` inside a quoted region emits one literal
+/// double quote and stays quoted.
+fn tokenize<U: Unit>(
+    src: &[U],
+    mut command_name: bool,
+    modern: bool,
+) -> Result<lib::Vec<lib::Vec<U>>, ParseError> {
+    enum State {
+        Init,
+        Unquoted,
+        Quoted,
+    }
+
+    let mut args = lib::Vec::new();
+    let mut token: lib::Vec<U> = lib::Vec::new();
+    let mut state = State::Init;
+    let mut i = 0;
+    let n = src.len();
+
+    while i < n {
+        let c = src[i];
+
+        match state {
+            State::Init => {
+                // Consume whitespace before the next argument
+                if c.is_whitespace_or_null() {
+                    if c.is_newline() {
+                        return Err(ParseError::ReachedUnescapedNewline);
+                    }
+                    i += 1;
+                    continue;
+                }
+
+                // Reprocess the first non-whitespace character as the start of the argument
+                state = State::Unquoted;
+            }
+
+            State::Unquoted => {
+                if c.is_whitespace_or_null() {
+                    args.push(lib::mem::take(&mut token));
+                    // Only the leading token is the command name
+                    command_name = false;
+                    state = State::Init;
+                    i += 1;
+                } else if c == U::DOUBLE_QUOTE {
+                    state = State::Quoted;
+                    i += 1;
+                } else if c == U::BACKSLASH && !command_name {
+                    // Backslashes are not special while parsing the command name
+                    i = parse_backslashes(src, i, &mut token);
+                } else {
+                    token.push(c);
+                    i += 1;
+                }
+            }
+
+            State::Quoted => {
+                if c == U::DOUBLE_QUOTE {
+                    if modern && i + 1 < n && src[i + 1] == U::DOUBLE_QUOTE {
+                        // Modern CRT: `""` inside quotes is one literal quote, staying quoted
+                        token.push(U::DOUBLE_QUOTE);
+                        i += 2;
+                    } else {
+                        // A double quote inside a quoted region ends it; under the LLVM rules an
+                        // immediately following double quote re-opens a new quoted region.
+                        state = State::Unquoted;
+                        i += 1;
+                    }
+                } else if c == U::BACKSLASH && !command_name {
+                    i = parse_backslashes(src, i, &mut token);
+                } else {
+                    token.push(c);
+                    i += 1;
+                }
+            }
+        }
+    }
+
+    // Flush any in-progress argument once the input ends
+    if !matches!(state, State::Init) {
+        args.push(token);
+    }
+
+    Ok(args)
+}
+
 /// Port of llvm's
 /// [cl::tokenizeWindowsCommandLine](https://llvm.org/doxygen/namespacellvm_1_1cl.html#a3b42fd69f84c0ceef44857e925613ee4) function
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -53,229 +227,123 @@ impl Parser {
     /// If the parser expects an executable path at the beginning, then the first string in the
     /// result will represent the executable path
     pub fn parse(self, s: &str) -> ParseResult {
-        enum State {
-            Init,
-            Quoted,
-            Unquoted,
-        }
-
-        let mut chars = s.chars();
-        let mut args = lib::Vec::new();
-        let mut arg = lib::String::new();
-        let mut state = State::Init;
-        let mut command_name = self.initial_command_name;
-        let mut remaining = s.chars().count();
-
-        /// If no arguments, will retrieve next character, or break out of loop if no more
-        /// characters
-        ///
-        /// If expression provided, will retrieve next character, or fail with given error
-        /// expression
-        macro_rules! next_char {
-            () => {{
-                match chars.next() {
-                    Some(c) => {
-                        if remaining > 0 {
-                            remaining -= 1;
-                        }
-                        c
-                    }
-                    None => break,
-                }
-            }};
-
-            ($err:expr) => {{
-                match chars.next() {
-                    Some(c) => {
-                        if remaining > 0 {
-                            remaining -= 1;
-                        }
-                        c
-                    }
-                    None => return Err($err),
-                }
-            }};
-        }
-
-        macro_rules! has_more_chars {
-            () => {
-                remaining > 0
-            };
-        }
+        let units: lib::Vec<char> = s.chars().collect();
+        let groups = tokenize(&units, self.initial_command_name, false)?;
+        Ok(groups
+            .into_iter()
+            .map(|g| g.into_iter().collect())
+            .collect())
+    }
 
-        macro_rules! no_more_chars {
-            () => {
-                remaining == 0
-            };
-        }
+    /// Parses a wide (UTF-16) command line into arguments, consuming the parser
+    ///
+    /// This accepts the raw buffer returned by `GetCommandLineW` directly, treating a `0` unit as
+    /// the terminator, and runs the exact same tokenization as [`parse`](Self::parse) over the
+    /// UTF-16 code units. Arguments are built with `OsString::from_wide` so that lone surrogates
+    /// in paths round-trip unchanged rather than being lost to a lossy UTF-8 conversion.
+    #[cfg(all(feature = "std", windows))]
+    pub fn parse_wide(self, s: &[u16]) -> Result<lib::Vec<std::ffi::OsString>, ParseError> {
+        use std::os::windows::ffi::OsStringExt;
+
+        let end = s.iter().position(|&u| u == 0).unwrap_or(s.len());
+        let groups = tokenize(&s[..end], self.initial_command_name, false)?;
+        Ok(groups
+            .into_iter()
+            .map(|g| std::ffi::OsString::from_wide(&g))
+            .collect())
+    }
+}
 
-        macro_rules! store_arg {
-            () => {{
-                args.push(arg);
-                arg = lib::String::new();
-            }};
-        }
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-        #[inline]
-        fn is_whitespace(c: char) -> bool {
-            c == ' ' || c == '\t' || c == '\r' || c == '\n'
-        }
+/// Parser for the modern Microsoft C runtime (VS2015+) command-line rules.
+///
+/// This shares the backslash/quote state machine with the LLVM-based [`Parser`](super::Parser),
+/// but differs in one documented way: while already inside a quoted region, encountering `""`
+/// emits a single literal double quote and *remains* quoted, rather than closing and immediately
+/// reopening the quoted region. Use this when you need to match how a real `argv` is built today.
+pub mod modern {
+    use super::{tokenize, ParseResult};
+    use crate::lib;
+
+    /// Port of the VS2015+ C runtime command-line parsing algorithm
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub struct Parser {
+        /// If true, treats first argument as executable path, which has special handling;
+        /// otherwise, will assume entire inner str is just arguments post-executable path
+        initial_command_name: bool,
+    }
 
-        #[inline]
-        fn is_whitespace_or_null(c: char) -> bool {
-            is_whitespace(c) || c == '\0'
+    impl Parser {
+        /// Creates a parser just for arguments
+        pub fn new() -> Self {
+            Self {
+                initial_command_name: false,
+            }
         }
 
-        // Windows treats whitespace, double quotes, and backslashes specially, except
-        // when parsing the first token of a full command line, in which case
-        // backslashes are not special.
-        #[inline]
-        fn is_special(c: char) -> bool {
-            is_whitespace_or_null(c) || c == '\\' || c == '"'
+        /// Creates a parser that will also parse an executable path at the beginning
+        pub fn full() -> Self {
+            Self {
+                initial_command_name: true,
+            }
         }
 
-        #[inline]
-        fn is_special_in_command_name(c: char) -> bool {
-            is_whitespace_or_null(c) || c == '"'
+        /// Parses a command line string into arguments, consuming the parser
+        pub fn parse(self, s: &str) -> ParseResult {
+            let units: lib::Vec<char> = s.chars().collect();
+            let groups = tokenize(&units, self.initial_command_name, true)?;
+            Ok(groups
+                .into_iter()
+                .map(|g| g.into_iter().collect())
+                .collect())
         }
 
-        /// Backslashes are interpreted in a rather complicated way in the Windows-style
-        /// command line, because backslashes are used both to separate path and to
-        /// escape double quote. This method consumes runs of backslashes as well as the
-        /// following double quote if it's escaped.
-        ///
-        ///  * If an even number of backslashes is followed by a double quote, one
-        ///    backslash is output for every pair of backslashes, and the last double
-        ///    quote remains unconsumed. The double quote will later be interpreted as
-        ///    the start or end of a quoted string in the main loop outside of this
-        ///    function.
+        /// Parses a wide (UTF-16) command line into arguments, consuming the parser
         ///
-        ///  * If an odd number of backslashes is followed by a double quote, one
-        ///    backslash is output for every pair of backslashes, and a double quote is
-        ///    output for the last pair of backslash-double quote. The double quote is
-        ///    consumed in this case.
-        ///
-        ///  * Otherwise, backslashes are interpreted literally.
-        macro_rules! parse_backslash {
-     () => {{
-         // Total number of backslashes
-         let mut cnt = 0;
-
-         while c == '\\' {
-             cnt += 1;
-             c = next_char!();
-         }
-
-         let followed_by_double_quote = has_more_chars!()
-
-   bool FollowedByDoubleQuote = (I != E && Src[I] == '"');
-   if (FollowedByDoubleQuote) {
-     Token.append(BackslashCount / 2, '\\');
-     if (BackslashCount % 2 == 0)
-       return I - 1;
-     Token.push_back('"');
-     return I;
-   }
-   Token.append(BackslashCount, '\\');
-   return I - 1;
-     }};
- }
-
-        loop {
-            // Get next character, exiting if we have run out of characters
-            let mut c = next_char!();
-
-            match state {
-                State::Init => {
-                    if !arg.is_empty() {
-                        return Err(ParseError::ArgNotEmptyInInitialState);
-                    }
-
-                    // Consume whitespace before argument
-                    while is_whitespace_or_null(c) {
-                        if c == '\n' {
-                            return Err(ParseError::ReachedUnescapedNewline);
-                        }
-                        c = next_char!();
-                    }
-
-                    if no_more_chars!() {
-                        break;
-                    }
-
-                    // Build up normal characters
-                    if command_name {
-                        while !is_special_in_command_name(c) {
-                            arg.push(c);
-                            c = next_char!();
-                        }
-                    } else {
-                        while !is_special(c) {
-                            arg.push(c);
-                            c = next_char!();
-                        }
-                    }
-
-                    if no_more_chars!() || is_whitespace_or_null(c) {
-                        store_arg!();
-                    } else if c == '"' {
-                        state = State::Quoted;
-                    } else if c == '\\' {
-                        if command_name {
-                            return Err(ParseError::CommandNameBackslash);
-                        }
-                        state = State::Unquoted;
-                    } else {
-                        unreachable!("unexpected special character");
-                    }
-                }
-
-                State::Quoted => {}
-
-                State::Unquoted => {}
-            }
-        }
-
-        // If we have one more active argument and not initializing, add it to our list
-        if !matches!(state, State::Init) && !arg.is_empty() {
-            args.push(arg);
+        /// Behaves like [`parse`](Self::parse) but over UTF-16 code units, preserving lone
+        /// surrogates via `OsString::from_wide`.
+        #[cfg(all(feature = "std", windows))]
+        pub fn parse_wide(
+            self,
+            s: &[u16],
+        ) -> Result<lib::Vec<std::ffi::OsString>, super::ParseError> {
+            use std::os::windows::ffi::OsStringExt;
+
+            let end = s.iter().position(|&u| u == 0).unwrap_or(s.len());
+            let groups = tokenize(&s[..end], self.initial_command_name, true)?;
+            Ok(groups
+                .into_iter()
+                .map(|g| std::ffi::OsString::from_wide(&g))
+                .collect())
         }
-
-        Ok(args)
     }
-}
-
-struct ParserState<'a> {
-    chars: core::str::Chars<'a>,
-    current_char: Option<char>,
-    remaining: usize,
-}
 
-impl<'a> ParserState<'a> {
-    pub fn next_char(&mut self) -> Option<char> {
-        self.current_char = self.chars.next();
-        if self.remaining > 0 {
-            self.remaining -= 1;
+    impl Default for Parser {
+        fn default() -> Self {
+            Self::new()
         }
-        self.current_char
-    }
-
-    pub fn is_done(&self) -> bool {
-        self.current_char.is_none()
     }
 
-    pub fn has_more_chars(&self) -> bool {
-        self.remaining > 0
-    }
+    #[cfg(test)]
+    mod tests {
+        use super::*;
 
-    pub fn remaining(&self) -> usize {
-        self.remaining
-    }
-}
+        #[test]
+        fn should_keep_double_double_quote_inside_a_quoted_region() {
+            let args = Parser::new().parse(r#""a""b""#).unwrap();
+            assert_eq!(args, &[r#"a"b"#]);
+        }
 
-impl<'a> PartialEq<char> for ParserState<'a> {
-    fn eq(&self, other: &char) -> bool {
-        self.current_char == Some(*other)
+        #[test]
+        fn should_match_documented_microsoft_examples() {
+            let args = Parser::full().parse(r#"EXE a\\\b d"e f"g h"#).unwrap();
+            assert_eq!(args, &["EXE", r"a\\\b", "de fg", "h"]);
+        }
     }
 }
 
@@ -335,19 +403,19 @@ mod tests {
         let args = Parser::new()
             .parse(r#"one \"two\" "three four" five"#)
             .unwrap();
-        assert_eq!(args, &["one", r#"\"two\""#, "three four", "five"]);
+        assert_eq!(args, &["one", r#""two""#, "three four", "five"]);
     }
 
     #[test]
     fn should_support_escaping_the_escape_character() {
         let args = Parser::new().parse(r"\\\\").unwrap();
-        assert_eq!(args, &[r"\\"]);
+        assert_eq!(args, &[r"\\\\"]);
     }
 
     #[test]
     fn should_support_escaping_the_escape_character_and_quote() {
         let args = Parser::new().parse(r#"\\\\\" some quote "#).unwrap();
-        assert_eq!(args, &[r#"\\"#, "some quote"]);
+        assert_eq!(args, &[r#"\\""#, "some", "quote"]);
     }
 
     #[test]
@@ -358,7 +426,7 @@ mod tests {
 
     #[test]
     fn should_support_quotes_within_quotes() {
-        let args = Parser::new().parse(r#"one "" three"#).unwrap();
-        assert_eq!(args, &[r#"\\"#, "some quote"]);
+        let args = Parser::new().parse(r#""a""b""#).unwrap();
+        assert_eq!(args, &["ab"]);
     }
 }
@@ -1,5 +1,23 @@
 use crate::lib;
 
+/// An error returned while splitting a PowerShell command line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// The command line ended while still inside a quoted region.
+    MissingClosingQuote,
+}
+
+impl lib::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut lib::fmt::Formatter) -> lib::fmt::Result {
+        match self {
+            Self::MissingClosingQuote => write!(f, "Missing closing quote"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseError {}
+
 /// Escapes special characters in a string, so that it will retain its literal
 /// meaning when used as a part of command in PowerShell.
 pub fn quote(s: &str) -> lib::String {
@@ -20,10 +38,103 @@ pub fn quote(s: &str) -> lib::String {
     quoted
 }
 
+/// Joins arguments into a single command line by quoting each with [`quote`].
+pub fn join<'a, I>(args: I) -> lib::String
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut out = lib::String::new();
+    for arg in args {
+        if !out.is_empty() {
+            out.push(' ');
+        }
+        out.push_str(&quote(arg));
+    }
+    out
+}
+
+/// Reports the token under the cursor at byte position `pos`, for use by shell completion.
+pub fn token_at(s: &str, pos: usize) -> crate::complete::TokenContext {
+    use crate::complete::{QuoteKind, TokenContext};
+
+    let mut index = 0;
+    let mut start: Option<usize> = None;
+    let mut value = lib::String::new();
+    let mut quote: Option<QuoteKind> = None;
+    let mut chars = s.char_indices().peekable();
+
+    while let Some(&(off, c)) = chars.peek() {
+        if off >= pos {
+            break;
+        }
+        chars.next();
+
+        // This mirrors [`split`], which only treats `"` as a quote (with a backtick escape inside);
+        // a single quote is an ordinary character here, so the cursor never enters
+        // [`QuoteKind::Single`].
+        match quote {
+            None => match c {
+                ' ' => {
+                    if start.is_some() {
+                        index += 1;
+                        start = None;
+                        value.clear();
+                    }
+                }
+                '"' => {
+                    start.get_or_insert(off);
+                    quote = Some(QuoteKind::Double);
+                }
+                _ => {
+                    start.get_or_insert(off);
+                    value.push(c);
+                }
+            },
+            Some(_) => match c {
+                '"' => quote = None,
+                '`' => {
+                    if let Some(&(o, next)) = chars.peek() {
+                        if o < pos {
+                            chars.next();
+                            value.push(next);
+                        }
+                    }
+                }
+                _ => value.push(c),
+            },
+        }
+    }
+
+    let start = start.unwrap_or(pos);
+    TokenContext {
+        index,
+        range: start..pos,
+        value,
+        quote,
+    }
+}
+
 /// Splits according to [Microsoft quoting rules][rules].
 ///
 /// [rules]: https://learn.microsoft.com/en-us/powershell/module/microsoft.powershell.core/about/about_quoting_rules?view=powershell-7.3.
+///
+/// An unterminated quote is accepted and tokenized as if it were closed at the end of input; use
+/// [`try_split`] to detect that case instead.
 pub fn split(s: &str) -> lib::Vec<lib::String> {
+    split_inner(s).0
+}
+
+/// Splits like [`split`], but fails with [`ParseError::MissingClosingQuote`] when the command line
+/// ends while still inside a quoted region.
+pub fn try_split(s: &str) -> Result<lib::Vec<lib::String>, ParseError> {
+    match split_inner(s) {
+        (args, false) => Ok(args),
+        (_, true) => Err(ParseError::MissingClosingQuote),
+    }
+}
+
+/// Tokenizes `s`, returning the arguments together with whether the scan ended inside a quote.
+fn split_inner(s: &str) -> (lib::Vec<lib::String>, bool) {
     let mut args = lib::Vec::new();
     let mut arg = lib::String::new();
     let mut chars = s.chars().peekable();
@@ -52,7 +163,7 @@ pub fn split(s: &str) -> lib::Vec<lib::String> {
         args.push(arg);
     }
 
-    args
+    (args, in_quotes)
 }
 
 #[cfg(test)]
@@ -71,7 +182,40 @@ mod tests {
         assert_eq!(quote("`"), "'`'");
 
         // Multiple special characters
-        assert_eq!(quote("''"), "''''''''");
+        assert_eq!(quote("''"), "''''''");
+    }
+
+    #[test]
+    fn should_report_token_under_cursor() {
+        use crate::complete::QuoteKind;
+
+        let ctx = token_at("Get-Process -Name \"My Pro", 25);
+        assert_eq!(ctx.index, 2);
+        assert_eq!(ctx.value, "My Pro");
+        assert_eq!(ctx.quote, Some(QuoteKind::Double));
+    }
+
+    #[test]
+    fn should_join_quoted_arguments() {
+        assert_eq!(join(["a", "b c"]), "'a' 'b c'");
+    }
+
+    #[test]
+    fn should_report_missing_closing_quote() {
+        assert_eq!(
+            try_split("Get-Item \"C:\\Program"),
+            Err(ParseError::MissingClosingQuote)
+        );
+        assert_eq!(
+            try_split("Get-Item \"C:\\Program Files\"").unwrap(),
+            vec!["Get-Item", "C:\\Program Files"]
+        );
+
+        // The infallible wrapper still tokenizes the unterminated input
+        assert_eq!(
+            split("Get-Item \"C:\\Program"),
+            vec!["Get-Item", "C:\\Program"]
+        );
     }
 
     #[test]
@@ -0,0 +1,325 @@
+use crate::lib;
+
+/// An error returned while splitting a POSIX shell command line
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// A single- or double-quoted region was never closed
+    UnterminatedQuote,
+
+    /// The input ended with a dangling, unescaped backslash
+    TrailingBackslash,
+}
+
+impl lib::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut lib::fmt::Formatter) -> lib::fmt::Result {
+        match self {
+            Self::UnterminatedQuote => write!(f, "Missing closing quote"),
+            Self::TrailingBackslash => write!(f, "Dangling backslash at end of input"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseError {}
+
+/// Splits a string using POSIX shell word-splitting with quote removal.
+///
+/// This performs only the tokenization and quote-removal steps of the shell grammar: there is no
+/// variable, command, arithmetic, tilde, or glob expansion. Single quotes preserve everything
+/// literally, double quotes allow `\` to escape only `$`, `` ` ``, `"`, `\`, and a newline, an
+/// unquoted backslash escapes the following character (and a backslash-newline is a line
+/// continuation that disappears), and an unquoted `#` at a word boundary begins a comment that
+/// runs to the end of the line. An unterminated quote or a trailing backslash is an error.
+pub fn split(s: &str) -> Result<lib::Vec<lib::String>, ParseError> {
+    enum State {
+        Delimiter,
+        Unquoted,
+        SingleQuoted,
+        DoubleQuoted,
+        UnquotedBackslash,
+        DoubleQuotedBackslash,
+        Comment,
+    }
+
+    let mut words = lib::Vec::new();
+    let mut word: Option<lib::String> = None;
+    let mut state = State::Delimiter;
+
+    for c in s.chars() {
+        match state {
+            State::Delimiter => match c {
+                ' ' | '\t' | '\n' => {}
+                '#' => state = State::Comment,
+                '\'' => {
+                    word.get_or_insert_with(lib::String::new);
+                    state = State::SingleQuoted;
+                }
+                '"' => {
+                    word.get_or_insert_with(lib::String::new);
+                    state = State::DoubleQuoted;
+                }
+                '\\' => state = State::UnquotedBackslash,
+                _ => {
+                    word.get_or_insert_with(lib::String::new).push(c);
+                    state = State::Unquoted;
+                }
+            },
+
+            State::Unquoted => match c {
+                ' ' | '\t' | '\n' => {
+                    if let Some(w) = word.take() {
+                        words.push(w);
+                    }
+                    state = State::Delimiter;
+                }
+                '\'' => state = State::SingleQuoted,
+                '"' => state = State::DoubleQuoted,
+                '\\' => state = State::UnquotedBackslash,
+                _ => word.get_or_insert_with(lib::String::new).push(c),
+            },
+
+            State::SingleQuoted => match c {
+                '\'' => state = State::Unquoted,
+                _ => word.get_or_insert_with(lib::String::new).push(c),
+            },
+
+            State::DoubleQuoted => match c {
+                '"' => state = State::Unquoted,
+                '\\' => state = State::DoubleQuotedBackslash,
+                _ => word.get_or_insert_with(lib::String::new).push(c),
+            },
+
+            // An unquoted backslash escapes the next character; a backslash-newline is a line
+            // continuation that simply disappears.
+            State::UnquotedBackslash => {
+                if c != '\n' {
+                    word.get_or_insert_with(lib::String::new).push(c);
+                }
+                state = State::Unquoted;
+            }
+
+            // Within double quotes a backslash escapes only these characters; anything else keeps
+            // the backslash literal, and a backslash-newline disappears.
+            State::DoubleQuotedBackslash => {
+                match c {
+                    '"' | '\\' | '`' | '$' => {
+                        word.get_or_insert_with(lib::String::new).push(c);
+                    }
+                    '\n' => {}
+                    _ => {
+                        let w = word.get_or_insert_with(lib::String::new);
+                        w.push('\\');
+                        w.push(c);
+                    }
+                }
+                state = State::DoubleQuoted;
+            }
+
+            State::Comment => {
+                if c == '\n' {
+                    state = State::Delimiter;
+                }
+            }
+        }
+    }
+
+    match state {
+        State::Delimiter | State::Unquoted | State::Comment => {
+            if let Some(w) = word.take() {
+                words.push(w);
+            }
+            Ok(words)
+        }
+        State::UnquotedBackslash => Err(ParseError::TrailingBackslash),
+        State::SingleQuoted | State::DoubleQuoted | State::DoubleQuotedBackslash => {
+            Err(ParseError::UnterminatedQuote)
+        }
+    }
+}
+
+/// Escapes a string so that it is read back as a single word by a POSIX shell.
+///
+/// A string made up entirely of safe characters is returned unchanged; otherwise it is wrapped in
+/// single quotes with any embedded single quote encoded as `'\''`.
+pub fn quote(s: &str) -> lib::String {
+    if !needs_quoting(s) {
+        return lib::String::from(s);
+    }
+
+    let mut quoted = lib::String::from("'");
+    for c in s.chars() {
+        if c == '\'' {
+            quoted.push_str("'\\''");
+        } else {
+            quoted.push(c);
+        }
+    }
+    quoted.push('\'');
+    quoted
+}
+
+/// Joins arguments into a single POSIX shell command line, quoting each as needed.
+pub fn join<'a, I>(args: I) -> lib::String
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut out = lib::String::new();
+    for arg in args {
+        if !out.is_empty() {
+            out.push(' ');
+        }
+        out.push_str(&quote(arg));
+    }
+    out
+}
+
+/// Reports the token under the cursor at byte position `pos`, for use by shell completion.
+pub fn token_at(s: &str, pos: usize) -> crate::complete::TokenContext {
+    use crate::complete::{QuoteKind, TokenContext};
+
+    let mut index = 0;
+    let mut start: Option<usize> = None;
+    let mut value = lib::String::new();
+    let mut quote: Option<QuoteKind> = None;
+    let mut chars = s.char_indices().peekable();
+
+    while let Some(&(off, c)) = chars.peek() {
+        if off >= pos {
+            break;
+        }
+        chars.next();
+
+        match quote {
+            None => match c {
+                ' ' | '\t' | '\n' => {
+                    if start.is_some() {
+                        index += 1;
+                        start = None;
+                        value.clear();
+                    }
+                }
+                '\'' => {
+                    start.get_or_insert(off);
+                    quote = Some(QuoteKind::Single);
+                }
+                '"' => {
+                    start.get_or_insert(off);
+                    quote = Some(QuoteKind::Double);
+                }
+                '\\' => {
+                    start.get_or_insert(off);
+                    if let Some(&(o, next)) = chars.peek() {
+                        if o < pos {
+                            chars.next();
+                            value.push(next);
+                        }
+                    }
+                }
+                _ => {
+                    start.get_or_insert(off);
+                    value.push(c);
+                }
+            },
+            Some(QuoteKind::Single) => match c {
+                '\'' => quote = None,
+                _ => value.push(c),
+            },
+            Some(QuoteKind::Double) => match c {
+                '"' => quote = None,
+                '\\' => match chars.peek() {
+                    Some(&(o, next @ ('"' | '\\' | '`' | '$'))) if o < pos => {
+                        chars.next();
+                        value.push(next);
+                    }
+                    _ => value.push('\\'),
+                },
+                _ => value.push(c),
+            },
+        }
+    }
+
+    let start = start.unwrap_or(pos);
+    TokenContext {
+        index,
+        range: start..pos,
+        value,
+        quote,
+    }
+}
+
+#[inline]
+fn needs_quoting(s: &str) -> bool {
+    if s.is_empty() {
+        return true;
+    }
+
+    !s.chars()
+        .all(|c| c.is_ascii_alphanumeric() || "-_./:=,%+@".contains(c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_split_across_multiple_situations() {
+        // Basic word splitting
+        assert_eq!(split("a b c").unwrap(), vec!["a", "b", "c"]);
+
+        // Single quotes preserve everything literally
+        assert_eq!(split("'single quoted'").unwrap(), vec!["single quoted"]);
+
+        // Double quotes do not expand variables
+        assert_eq!(split(r#""double $x""#).unwrap(), vec!["double $x"]);
+
+        // Unquoted backslash escapes the following character
+        assert_eq!(split(r"a\ b").unwrap(), vec!["a b"]);
+
+        // A `#` at a word boundary starts a comment
+        assert_eq!(split("a # comment").unwrap(), vec!["a"]);
+
+        // A `#` inside a word is literal
+        assert_eq!(split("a#b").unwrap(), vec!["a#b"]);
+
+        // A backslash-newline is a line continuation that disappears
+        assert_eq!(split("a\\\nb").unwrap(), vec!["ab"]);
+
+        // Inside double quotes a backslash before an ordinary character stays literal
+        assert_eq!(split(r#""a\b""#).unwrap(), vec![r"a\b"]);
+    }
+
+    #[test]
+    fn should_error_on_unterminated_quote() {
+        assert_eq!(split("'abc"), Err(ParseError::UnterminatedQuote));
+        assert_eq!(split("\\"), Err(ParseError::TrailingBackslash));
+    }
+
+    #[test]
+    fn should_quote_across_multiple_situations() {
+        assert_eq!(quote(""), "''");
+        assert_eq!(quote("plain"), "plain");
+        assert_eq!(quote("has space"), "'has space'");
+        assert_eq!(quote("it's"), "'it'\\''s'");
+    }
+
+    #[test]
+    fn should_report_token_under_cursor() {
+        use crate::complete::QuoteKind;
+
+        let ctx = token_at("git commit 'work in prog", 24);
+        assert_eq!(ctx.index, 2);
+        assert_eq!(ctx.value, "work in prog");
+        assert_eq!(ctx.quote, Some(QuoteKind::Single));
+
+        // Outside any quote when the cursor follows a completed word
+        let ctx = token_at("ls -la ", 7);
+        assert_eq!(ctx.index, 2);
+        assert_eq!(ctx.value, "");
+        assert_eq!(ctx.quote, None);
+    }
+
+    #[test]
+    fn should_join_with_spaces() {
+        assert_eq!(join(["a", "b c"]), "a 'b c'");
+    }
+}
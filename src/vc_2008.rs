@@ -140,111 +140,385 @@
 //! ![parsingrules](https://user-images.githubusercontent.com/2481802/182859707-008040c5-39eb-4e2a-949a-89911fa5a973.png)
 use crate::lib;
 
-/// Splits a command line string into arguments using the VC++ 2008 rules.
-pub fn split(s: &str) -> lib::Vec<lib::String> {
-    let mut args = lib::Vec::new();
-    let mut arg = lib::String::new();
-    let mut backslash_cnt = 0;
-    let mut in_quote = false;
-    let mut chars = s.chars().peekable();
+/// An error returned while splitting a command line under the VC++ 2008 rules.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// The command line ended while still inside a double quoted part.
+    MissingClosingQuote,
+}
 
-    while let Some(c) = chars.next() {
-        // Check the next character to see if it is a quote
-        let is_quote_next = chars.peek() == Some(&'"');
+impl lib::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut lib::fmt::Formatter) -> lib::fmt::Result {
+        match self {
+            Self::MissingClosingQuote => write!(f, "Missing closing quote"),
+        }
+    }
+}
 
-        // True if we have an odd number of backslashes
-        let even_backslash_cnt = backslash_cnt % 2 == 0;
+#[cfg(feature = "std")]
+impl std::error::Error for ParseError {}
 
-        // Flag to skip adding the character (for use when starting a quote)
-        let mut skip_adding_char = false;
+/// A single code unit of a command line.
+///
+/// The backslash/quote state machine is written once against this trait so that the identical
+/// rules run over UTF-8 `char`s (see [`split`]) or raw UTF-16 `u16` units (see [`split_wide`])
+/// without duplicating the logic.
+trait Unit: Copy + PartialEq {
+    /// The backslash code unit
+    const BACKSLASH: Self;
 
-        match c {
-            // Backslash should just increase the count without immediately adding the char
-            '\\' => {
-                backslash_cnt += 1;
-                continue;
-            }
+    /// The double quote code unit
+    const QUOTE: Self;
 
-            // Quote with even number of backslashes and already within a quote and next
-            // character is also a quote
-            '"' if even_backslash_cnt && in_quote && is_quote_next => {
-                // Move to second quote (essentially skip it since both are ")
-                let _ = chars.next();
+    /// Whitespace or a null terminator, any of which separates arguments
+    fn is_whitespace_or_null(self) -> bool;
+}
 
-                // Set backslash cnt to N/2 so we add N/2
-                backslash_cnt /= 2;
-            }
+impl Unit for char {
+    const BACKSLASH: char = '\\';
+    const QUOTE: char = '"';
 
-            // Quote with even number of backslashes and already within a quote
-            '"' if even_backslash_cnt && in_quote => {
-                // Flag that we are no longer in a quote
-                in_quote = false;
+    fn is_whitespace_or_null(self) -> bool {
+        is_whitespace_or_null(self)
+    }
+}
 
-                // Don't add this doublequote as it is just marking the end of a quote
-                skip_adding_char = true;
+impl Unit for u16 {
+    const BACKSLASH: u16 = b'\\' as u16;
+    const QUOTE: u16 = b'"' as u16;
 
-                // Set backslash cnt to N/2 so we add N/2
-                //
-                // 2N backslashes -> N backslashes + end quote
-                backslash_cnt /= 2;
-            }
+    fn is_whitespace_or_null(self) -> bool {
+        self == 0
+            || self == b' ' as u16
+            || self == b'\t' as u16
+            || self == b'\r' as u16
+            || self == b'\n' as u16
+    }
+}
 
-            // Quote with even number of backslashes, but not within a quote
-            '"' if even_backslash_cnt => {
-                // Flag that we are now in a quote
-                in_quote = true;
+/// Runs the VC++ 2008 state machine over a slice of code units, returning the argument groups
+/// together with whether the scan ended inside a double quoted part.
+fn tokenize<U: Unit>(src: &[U]) -> (lib::Vec<lib::Vec<U>>, bool) {
+    let mut args = lib::Vec::new();
+    let mut arg: lib::Vec<U> = lib::Vec::new();
+    let mut backslash_cnt: usize = 0;
+    let mut in_quote = false;
+    // Tracks whether the current argument has been started, so that an explicit empty argument
+    // (e.g. `""`) is preserved even though it contributes no characters.
+    let mut arg_started = false;
+
+    let mut i = 0;
+    while i < src.len() {
+        let c = src[i];
+        let is_quote_next = src.get(i + 1) == Some(&U::QUOTE);
+        let even_backslash_cnt = backslash_cnt.is_multiple_of(2);
+        let mut skip_adding_char = false;
 
-                // Don't add this doublequote as it is just marking the start of a quote
+        if c == U::BACKSLASH {
+            // Backslash just increases the count without immediately adding the char
+            backslash_cnt += 1;
+            i += 1;
+            continue;
+        } else if c == U::QUOTE {
+            if even_backslash_cnt && in_quote && is_quote_next {
+                // `""` within a quoted part yields one literal quote and stays quoted
+                i += 1;
+                backslash_cnt /= 2;
+            } else if even_backslash_cnt && in_quote {
+                // 2N backslashes -> N backslashes + end quote
+                in_quote = false;
                 skip_adding_char = true;
-
-                // Set backslash cnt to N/2 so we add N/2
-                //
+                backslash_cnt /= 2;
+            } else if even_backslash_cnt {
                 // 2N backslashes -> N backslashes + start quote
+                in_quote = true;
+                skip_adding_char = true;
                 backslash_cnt /= 2;
-            }
-
-            // Quote with odd number of backslashes
-            '"' => {
-                // Set backslash cnt to N/2 so we add N/2
-                //
+            } else {
                 // 2N + 1 backslashes -> N backslashes + literal quote
                 backslash_cnt /= 2;
             }
-
-            // Quote with odd number of backslashes or anything else
-            _ => {}
         }
 
         // Add backslashes to arg and reset counter
         if backslash_cnt > 0 {
-            add_n_backslashes(&mut arg, backslash_cnt);
+            for _ in 0..backslash_cnt {
+                arg.push(U::BACKSLASH);
+            }
             backslash_cnt = 0;
+            arg_started = true;
+        }
+
+        // A double quote begins or ends an argument even when it adds no characters
+        if c == U::QUOTE {
+            arg_started = true;
         }
 
-        // If we are in a quote, then we should consume everything,
-        // otherwise once we hit whitespace we want to finish the arg
-        if !in_quote && is_whitespace_or_null(c) {
-            if !arg.is_empty() {
-                args.push(arg);
-                arg = lib::String::new();
+        // If we are in a quote, then we should consume everything, otherwise once we hit
+        // whitespace we want to finish the arg
+        if !in_quote && c.is_whitespace_or_null() {
+            if arg_started {
+                args.push(lib::mem::take(&mut arg));
+                arg_started = false;
             }
         } else if !skip_adding_char {
             arg.push(c);
+            arg_started = true;
         }
+
+        i += 1;
     }
 
     // Add any remaining backslashes as these were at the end of the string
     if backslash_cnt > 0 {
-        add_n_backslashes(&mut arg, backslash_cnt);
+        for _ in 0..backslash_cnt {
+            arg.push(U::BACKSLASH);
+        }
+        arg_started = true;
     }
 
-    if !arg.is_empty() {
+    if arg_started {
         args.push(arg);
     }
 
+    (args, in_quote)
+}
+
+/// Splits a command line string into arguments using the VC++ 2008 rules.
+///
+/// An unterminated double quoted part is accepted and tokenized as if it were closed at the end of
+/// input; use [`try_split`] to detect that case instead.
+pub fn split(s: &str) -> lib::Vec<lib::String> {
+    split_inner(s).0
+}
+
+/// Splits like [`split`], but fails with [`ParseError::MissingClosingQuote`] when the command line
+/// ends while still inside a double quoted part.
+pub fn try_split(s: &str) -> Result<lib::Vec<lib::String>, ParseError> {
+    match split_inner(s) {
+        (args, false) => Ok(args),
+        (_, true) => Err(ParseError::MissingClosingQuote),
+    }
+}
+
+/// Tokenizes `s`, returning the arguments together with whether the scan ended inside a quote.
+fn split_inner(s: &str) -> (lib::Vec<lib::String>, bool) {
+    let units: lib::Vec<char> = s.chars().collect();
+    let (groups, in_quote) = tokenize(&units);
+    let args = groups
+        .into_iter()
+        .map(|g| g.into_iter().collect())
+        .collect();
+    (args, in_quote)
+}
+
+/// Splits a wide (UTF-16) command line into `OsString` arguments using the VC++ 2008 rules.
+///
+/// This accepts the raw buffer returned by `GetCommandLineW` and runs the exact same
+/// backslash/quote state machine as [`split`] over UTF-16 code units, comparing against the units
+/// for space, tab, carriage return, line feed, NUL, `"`, and `\` and copying every other unit
+/// through unchanged. Arguments are
+/// built with `OsString::from_wide` so that lone surrogates — which are not valid UTF-8 — round
+/// trip intact rather than being lost to a lossy conversion.
+#[cfg(all(feature = "std", windows))]
+pub fn split_wide(s: &[u16]) -> lib::Vec<std::ffi::OsString> {
+    use std::os::windows::ffi::OsStringExt;
+
+    split_wide_units(s)
+        .into_iter()
+        .map(|g| std::ffi::OsString::from_wide(&g))
+        .collect()
+}
+
+/// Splits an [`OsStr`](std::ffi::OsStr) command line into arguments using the VC++ 2008 rules.
+///
+/// This is a convenience wrapper around [`split_wide`] that encodes the input as UTF-16 first.
+#[cfg(all(feature = "std", windows))]
+pub fn split_os(s: &std::ffi::OsStr) -> lib::Vec<std::ffi::OsString> {
+    use std::os::windows::ffi::OsStrExt;
+
+    let units: lib::Vec<u16> = s.encode_wide().collect();
+    split_wide(&units)
+}
+
+/// Tokenizes a UTF-16 command line into argument groups of code units.
+#[cfg(any(windows, test))]
+fn split_wide_units(s: &[u16]) -> lib::Vec<lib::Vec<u16>> {
+    tokenize(s).0
+}
+
+/// Escapes a string so that it will be read back as a single argument by [`split`].
+///
+/// An argument containing no whitespace (space, tab, carriage return, line feed), NUL, or double
+/// quote is returned unchanged; otherwise it is wrapped in double quotes, any run of backslashes
+/// immediately preceding a double quote (or the closing quote) is doubled, and interior double
+/// quotes are escaped as `\"`.
+pub fn quote(s: &str) -> lib::String {
+    if !s.is_empty() && !s.chars().any(|c| c == '"' || is_whitespace_or_null(c)) {
+        return lib::String::from(s);
+    }
+
+    let mut quoted = lib::String::from("\"");
+    let mut chars = s.chars().peekable();
+
+    loop {
+        let mut num_backslashes = 0;
+        while chars.peek() == Some(&'\\') {
+            chars.next();
+            num_backslashes += 1;
+        }
+
+        match chars.next() {
+            // Backslashes before the closing quote must be doubled so they stay literal
+            None => {
+                add_n_backslashes(&mut quoted, num_backslashes * 2);
+                break;
+            }
+            // Backslashes before an interior quote are doubled and the quote is escaped
+            Some('"') => {
+                add_n_backslashes(&mut quoted, num_backslashes * 2 + 1);
+                quoted.push('"');
+            }
+            // Backslashes not followed by a quote are emitted literally
+            Some(c) => {
+                add_n_backslashes(&mut quoted, num_backslashes);
+                quoted.push(c);
+            }
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+/// Joins arguments into a single command line that [`split`] reproduces exactly.
+pub fn join<'a, I>(args: I) -> lib::String
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut out = lib::String::new();
+    for arg in args {
+        if !out.is_empty() {
+            out.push(' ');
+        }
+        out.push_str(&quote(arg));
+    }
+    out
+}
+
+/// Splits a full command line whose first token is the program name (`argv[0]`).
+///
+/// Windows parses the executable path by different rules than the remaining arguments: a leading
+/// run of whitespace is skipped, then if the line begins with a double quote the program name is
+/// everything up to the next double quote (backslashes are literal and quotes act only as plain
+/// delimiters); otherwise it ends at the first space or tab with no escape processing at all. The
+/// rest of the line is then tokenized with the usual [`split`] rules. An empty input yields an
+/// empty program name.
+pub fn split_with_program_name(s: &str) -> lib::Vec<lib::String> {
+    let mut args = lib::Vec::new();
+    let mut prog = lib::String::new();
+
+    // Skip a leading run of whitespace before the program name
+    let mut it = s.char_indices();
+    let mut cur = it.next();
+    while let Some((_, c)) = cur {
+        if c == ' ' || c == '\t' {
+            cur = it.next();
+        } else {
+            break;
+        }
+    }
+
+    let rest_start = match cur {
+        None => {
+            // Empty (or all-whitespace) input yields a single empty program name
+            args.push(prog);
+            return args;
+        }
+        Some((_, '"')) => loop {
+            // Quoted program name: literal until the next double quote
+            match it.next() {
+                Some((j, '"')) => break j + '"'.len_utf8(),
+                Some((_, c)) => prog.push(c),
+                None => break s.len(),
+            }
+        },
+        Some((_, first)) => {
+            // Unquoted program name: ends at the first whitespace, no escaping
+            prog.push(first);
+            loop {
+                match it.next() {
+                    Some((j, c)) if c == ' ' || c == '\t' => break j,
+                    Some((_, c)) => prog.push(c),
+                    None => break s.len(),
+                }
+            }
+        }
+    };
+
+    args.push(prog);
+    args.extend(split(&s[rest_start..]));
     args
 }
 
+/// Reports the token under the cursor at byte position `pos`, for use by shell completion.
+///
+/// This treats each `"` as a toggle of the double quoted part and each `\"` as a literal quote, so
+/// a completer can recover the word being typed and whether it sits inside an open quote. Unlike
+/// [`split`], it does not halve a run of backslashes before a `"`, and a `""` inside a double
+/// quoted part toggles twice instead of adding a literal quote to the value.
+pub fn token_at(s: &str, pos: usize) -> crate::complete::TokenContext {
+    use crate::complete::{QuoteKind, TokenContext};
+
+    let mut index = 0;
+    let mut start: Option<usize> = None;
+    let mut value = lib::String::new();
+    let mut quote: Option<QuoteKind> = None;
+    let mut chars = s.char_indices().peekable();
+
+    while let Some(&(off, c)) = chars.peek() {
+        if off >= pos {
+            break;
+        }
+        chars.next();
+
+        match c {
+            c if quote.is_none() && is_whitespace_or_null(c) => {
+                if start.is_some() {
+                    index += 1;
+                    start = None;
+                    value.clear();
+                }
+            }
+            '\\' if matches!(chars.peek(), Some(&(o, '"')) if o < pos) => {
+                chars.next();
+                start.get_or_insert(off);
+                value.push('"');
+            }
+            '"' => {
+                start.get_or_insert(off);
+                quote = if quote == Some(QuoteKind::Double) {
+                    None
+                } else {
+                    Some(QuoteKind::Double)
+                };
+            }
+            _ => {
+                start.get_or_insert(off);
+                value.push(c);
+            }
+        }
+    }
+
+    let start = start.unwrap_or(pos);
+    TokenContext {
+        index,
+        range: start..pos,
+        value,
+        quote,
+    }
+}
+
 #[inline]
 fn add_n_backslashes(s: &mut lib::String, n: usize) {
     for _ in 0..n {
@@ -341,6 +615,125 @@ mod tests {
         assert_eq!(args, &["a", "b", "c"]);
     }
 
+    #[test]
+    fn should_report_token_under_cursor() {
+        use crate::complete::QuoteKind;
+
+        let ctx = token_at(r#"app --path "C:\Program Fi"#, 25);
+        assert_eq!(ctx.index, 2);
+        assert_eq!(ctx.value, r"C:\Program Fi");
+        assert_eq!(ctx.quote, Some(QuoteKind::Double));
+    }
+
+    #[test]
+    fn should_report_missing_closing_quote() {
+        assert_eq!(
+            try_split(r#""one" "two"#),
+            Err(ParseError::MissingClosingQuote)
+        );
+        assert_eq!(try_split(r#""one" "two""#).unwrap(), vec!["one", "two"]);
+
+        // The infallible wrapper still tokenizes the unterminated input
+        assert_eq!(split(r#""one" "two"#), vec!["one", "two"]);
+    }
+
+    #[cfg(all(feature = "std", windows))]
+    #[test]
+    fn should_split_wide_preserving_lone_surrogates() {
+        use std::os::windows::ffi::OsStrExt;
+
+        let input: lib::Vec<u16> = "\"a b\" c".encode_utf16().collect();
+        assert_eq!(
+            split_wide(&input),
+            vec![std::ffi::OsString::from("a b"), std::ffi::OsString::from("c")]
+        );
+
+        // A lone high surrogate is not valid UTF-8 yet must survive the split unchanged
+        let surrogate = [0x61u16, 0xD800, 0x62];
+        let parts = split_wide(&surrogate);
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].encode_wide().collect::<lib::Vec<_>>(), surrogate);
+    }
+
+    #[test]
+    fn should_tokenize_wide_units_matching_split() {
+        // The u16 tokenizer produces the same argument groups as the char split for valid UTF-16
+        let input: lib::Vec<u16> = r#""a b" c"#.encode_utf16().collect();
+        let expected: lib::Vec<lib::Vec<u16>> = vec![
+            "a b".encode_utf16().collect(),
+            "c".encode_utf16().collect(),
+        ];
+        assert_eq!(split_wide_units(&input), expected);
+
+        // A lone high surrogate is not valid UTF-8/UTF-16 yet must survive as a code unit
+        let surrogate = [0x61u16, 0xD800, 0x62];
+        assert_eq!(split_wide_units(&surrogate), vec![vec![0x61u16, 0xD800, 0x62]]);
+    }
+
+    #[test]
+    fn should_split_with_program_name() {
+        // A quoted program name keeps backslashes literal and treats quotes as bare delimiters
+        assert_eq!(
+            split_with_program_name(r#""C:\Program Files\app.exe" one "two three""#),
+            vec![r"C:\Program Files\app.exe", "one", "two three"]
+        );
+
+        // An unquoted program name ends at the first whitespace with no escaping
+        assert_eq!(
+            split_with_program_name(r"C:\app.exe one two"),
+            vec![r"C:\app.exe", "one", "two"]
+        );
+
+        // Leading whitespace is skipped
+        assert_eq!(
+            split_with_program_name("   prog arg"),
+            vec!["prog", "arg"]
+        );
+
+        // Empty input yields a single empty program name
+        assert_eq!(split_with_program_name(""), vec![""]);
+    }
+
+    #[test]
+    fn should_quote_across_multiple_situations() {
+        // Safe words are emitted unchanged
+        assert_eq!(quote("hello"), "hello");
+
+        // Empty strings become an empty quoted part
+        assert_eq!(quote(""), r#""""#);
+
+        // Whitespace is enclosed in double quotes
+        assert_eq!(quote("hello world"), r#""hello world""#);
+
+        // Trailing backslashes before the closing quote are doubled
+        assert_eq!(quote(r"C:\TEST A\"), r#""C:\TEST A\\""#);
+
+        // Interior quotes are escaped and preceding backslashes doubled
+        assert_eq!(quote(r#"a"b"#), r#""a\"b""#);
+    }
+
+    #[test]
+    fn should_round_trip_through_split() {
+        let cases: &[&[&str]] = &[
+            &["hello"],
+            &["one", "two three", "four"],
+            &[""],
+            &["a", "", "b"],
+            &[r"C:\TEST A\", "arg"],
+            &[r#"quote"inside"#],
+            &[r"trailing\\"],
+            &[r#"a"b"c"#],
+            &["\\"],
+            &[r"a b\", "\"", "c d"],
+            &[r#""leading quote"#, r#"trailing quote""#],
+            &[r"C:\dir\", "", r#"mixed "bag" \\"#],
+        ];
+
+        for args in cases {
+            assert_eq!(&split(&join(args.iter().copied())), args);
+        }
+    }
+
     // Extra tests from https://daviddeley.com/autohotkey/parameters/parameters.htm#WIN
     mod extra_from_david_deley {
         use super::*;